@@ -1,14 +1,36 @@
+use std::collections::HashSet;
+
+#[cfg(feature = "serde")]
+use std::io::{Read, Write};
+
+use arrayvec::ArrayVec;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Game {
     width: usize,
     height: usize,
     mines: Vec<Coords>,
     cells: Vec<CellState>,
+    placed: bool,
+    pending_mines: usize,
+    mine_grid: Vec<bool>,
+    neighbor_counts: Vec<u8>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    log: Option<Vec<(crate::replay::Move, RevealResult)>>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    recording_seed: Option<Vec<CellState>>,
 }
 
+// The moves recorded since `start_recording` plus the board state at that
+// point, handed off to the replay subsystem via `take_log`.
+type RecordingLog = (Vec<CellState>, Vec<(crate::replay::Move, RevealResult)>);
+
 pub type Coords = (usize, usize);
 
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum RevealResult {
     GameOver,
@@ -16,6 +38,7 @@ pub enum RevealResult {
     Win,
 }
 
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum CellState {
     Hidden,
@@ -55,6 +78,64 @@ pub enum ToggleFlagError {
     CellRevealed(Coords),
 }
 
+#[derive(Debug, Error)]
+pub enum RevealError {
+    #[error(transparent)]
+    InvalidCoords(#[from] InvalidCoords),
+    #[error(
+        "Cannot place {mines} mines while keeping the first click at {at:?} and its neighbors safe ({available} cells available)"
+    )]
+    FieldTooSmall {
+        at: Coords,
+        mines: usize,
+        available: usize,
+    },
+}
+
+#[cfg(feature = "serde")]
+#[derive(Debug, Error)]
+#[error("Failed to save game: {0}")]
+pub struct SaveError(#[from] ciborium::ser::Error<std::io::Error>);
+
+#[cfg(feature = "serde")]
+#[derive(Debug, Error)]
+pub enum InvalidSaveData {
+    #[error("expected {expected} cells for a {width}x{height} field, got {actual}")]
+    CellCount {
+        width: usize,
+        height: usize,
+        expected: usize,
+        actual: usize,
+    },
+    #[error("expected {expected} mine grid entries for a {width}x{height} field, got {actual}")]
+    MineGridCount {
+        width: usize,
+        height: usize,
+        expected: usize,
+        actual: usize,
+    },
+    #[error("expected {expected} neighbor counts for a {width}x{height} field, got {actual}")]
+    NeighborCountsCount {
+        width: usize,
+        height: usize,
+        expected: usize,
+        actual: usize,
+    },
+    #[error("mine at {0:?} is out of bounds")]
+    MineOutOfBounds(Coords),
+    #[error("duplicate mine at {0:?}")]
+    DuplicateMine(Coords),
+}
+
+#[cfg(feature = "serde")]
+#[derive(Debug, Error)]
+pub enum LoadError {
+    #[error("Failed to load game: {0}")]
+    Deserialize(#[from] ciborium::de::Error<std::io::Error>),
+    #[error("Failed to load game: {0}")]
+    InvalidData(#[from] InvalidSaveData),
+}
+
 impl Game {
     pub fn new(
         field_width: usize,
@@ -71,8 +152,14 @@ impl Game {
             Ok(Self {
                 width: field_width,
                 height: field_height,
-                mines: generate_random_mines(field_width, field_height, mine_count),
+                mines: Vec::new(),
                 cells: vec![CellState::Hidden; field_width * field_height],
+                placed: false,
+                pending_mines: mine_count,
+                mine_grid: vec![false; field_width * field_height],
+                neighbor_counts: vec![0; field_width * field_height],
+                log: None,
+                recording_seed: None,
             })
         }
     }
@@ -83,9 +170,30 @@ impl Game {
             height: field_height,
             mines: Vec::new(),
             cells: vec![CellState::Hidden; field_width * field_height],
+            placed: true,
+            pending_mines: 0,
+            mine_grid: vec![false; field_width * field_height],
+            neighbor_counts: vec![0; field_width * field_height],
+            log: None,
+            recording_seed: None,
         }
     }
 
+    pub fn start_recording(&mut self) {
+        self.recording_seed = Some(self.cells.clone());
+        self.log = Some(Vec::new());
+    }
+
+    pub fn take_log(&mut self) -> Option<RecordingLog> {
+        let seed = self.recording_seed.take()?;
+        let moves = self.log.take()?;
+        Some((seed, moves))
+    }
+
+    pub fn mines(&self) -> &[Coords] {
+        &self.mines
+    }
+
     pub fn place_mine(&mut self, at: Coords) -> Result<(), PlaceError> {
         self.index(at)?;
 
@@ -93,6 +201,7 @@ impl Game {
             Err(PlaceError::MineAlreadyAt(at))
         } else {
             self.mines.push(at);
+            self.index_mine(at);
             Ok(())
         }
     }
@@ -110,35 +219,78 @@ impl Game {
                 return Err(ToggleFlagError::CellRevealed(at));
             }
         }
+        // Flagging never ends the game, so there's no richer result to record.
+        self.record(crate::replay::Move::ToggleFlag(at), RevealResult::Continue);
         Ok(())
     }
 
-    pub fn reveal(&mut self, at: Coords) -> Result<RevealResult, InvalidCoords> {
+    pub fn reveal(&mut self, at: Coords) -> Result<RevealResult, RevealError> {
         self.index(at)?;
 
-        if self.is_mine_at(at) {
-            return Ok(RevealResult::GameOver);
+        if !self.placed {
+            self.place_pending_mines(at)?;
         }
 
-        self.floodfill_reveal(at);
+        let result = if self.is_mine_at(at) {
+            RevealResult::GameOver
+        } else {
+            self.floodfill_reveal(at);
+            self.aggregate_result()
+        };
+
+        self.record(crate::replay::Move::Reveal(at), result);
 
-        let remaining_not_revealed = self
-            .cells
+        Ok(result)
+    }
+
+    pub fn chord(&mut self, at: Coords) -> Result<RevealResult, InvalidCoords> {
+        let revealed_count = match self.cell_at(at)? {
+            CellState::Revealed(n) => n,
+            CellState::Hidden | CellState::Flagged => return Ok(RevealResult::Continue),
+        };
+
+        let neighbors = self.neighbors(at);
+        let flagged_count = neighbors
             .iter()
-            .filter(|cell| matches!(cell, CellState::Hidden | CellState::Flagged))
-            .count();
+            .filter(|&&neighbor| self.cell_at(neighbor).unwrap() == CellState::Flagged)
+            .count() as u8;
 
-        if remaining_not_revealed == self.mine_count() {
-            Ok(RevealResult::Win)
-        } else {
-            Ok(RevealResult::Continue)
+        if flagged_count != revealed_count {
+            return Ok(RevealResult::Continue);
         }
+
+        let mut hit_mine = false;
+        for neighbor in neighbors {
+            if self.cell_at(neighbor).unwrap() == CellState::Hidden {
+                if self.is_mine_at(neighbor) {
+                    hit_mine = true;
+                } else {
+                    self.floodfill_reveal(neighbor);
+                }
+            }
+        }
+
+        let result = if hit_mine {
+            RevealResult::GameOver
+        } else {
+            self.aggregate_result()
+        };
+
+        self.record(crate::replay::Move::Chord(at), result);
+
+        Ok(result)
     }
 
     pub fn cell_at(&self, at: Coords) -> Result<CellState, InvalidCoords> {
         Ok(self.cells[self.index(at)?])
     }
 
+    // Used by the replay subsystem to seed a reconstruction from a board state
+    // that wasn't built up purely by replaying logged moves.
+    pub(crate) fn restore_cells(&mut self, cells: Vec<CellState>) {
+        self.cells = cells;
+    }
+
     pub fn mine_count(&self) -> usize {
         self.mines.len()
     }
@@ -151,39 +303,148 @@ impl Game {
         self.height
     }
 
-    fn floodfill_reveal(&mut self, start: Coords) {
-        let mut to_reveal = Vec::new();
-        to_reveal.push(start);
+    #[cfg(feature = "serde")]
+    pub fn save_to_writer(&self, writer: impl Write) -> Result<(), SaveError> {
+        ciborium::ser::into_writer(self, writer)?;
+        Ok(())
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn load_from_reader(reader: impl Read) -> Result<Self, LoadError> {
+        let game: Self = ciborium::de::from_reader(reader)?;
+        game.validate_shape()?;
+        Ok(game)
+    }
+
+    #[cfg(feature = "serde")]
+    fn validate_shape(&self) -> Result<(), InvalidSaveData> {
+        let expected = self.width * self.height;
+
+        if self.cells.len() != expected {
+            return Err(InvalidSaveData::CellCount {
+                width: self.width,
+                height: self.height,
+                expected,
+                actual: self.cells.len(),
+            });
+        }
+        if self.mine_grid.len() != expected {
+            return Err(InvalidSaveData::MineGridCount {
+                width: self.width,
+                height: self.height,
+                expected,
+                actual: self.mine_grid.len(),
+            });
+        }
+        if self.neighbor_counts.len() != expected {
+            return Err(InvalidSaveData::NeighborCountsCount {
+                width: self.width,
+                height: self.height,
+                expected,
+                actual: self.neighbor_counts.len(),
+            });
+        }
+
+        let mut seen = HashSet::new();
+        for &mine in &self.mines {
+            if mine.0 >= self.width || mine.1 >= self.height {
+                return Err(InvalidSaveData::MineOutOfBounds(mine));
+            }
+            if !seen.insert(mine) {
+                return Err(InvalidSaveData::DuplicateMine(mine));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn place_pending_mines(&mut self, at: Coords) -> Result<(), RevealError> {
+        let excluded = self.first_click_exclusion(at);
+        let available = self.width * self.height - excluded.len();
+
+        if self.pending_mines > available {
+            return Err(RevealError::FieldTooSmall {
+                at,
+                mines: self.pending_mines,
+                available,
+            });
+        }
+
+        let mines = generate_random_mines(self.width, self.height, self.pending_mines, &excluded);
+        for &mine in &mines {
+            self.index_mine(mine);
+        }
+        self.mines = mines;
+        self.placed = true;
+        Ok(())
+    }
+
+    fn index_mine(&mut self, at: Coords) {
+        let idx = self.index(at).unwrap();
+        self.mine_grid[idx] = true;
+        for neighbor in self.neighbors(at) {
+            let neighbor_idx = self.index(neighbor).unwrap();
+            self.neighbor_counts[neighbor_idx] += 1;
+        }
+    }
+
+    fn first_click_exclusion(&self, at: Coords) -> HashSet<Coords> {
+        let mut excluded: HashSet<Coords> = self.neighbors(at).into_iter().collect();
+        excluded.insert(at);
+        excluded
+    }
+
+    fn neighbors(&self, at: Coords) -> ArrayVec<Coords, 8> {
+        let (at_x, at_y) = at;
+        let mut result = ArrayVec::new();
+
+        for dx in -1..=1i64 {
+            for dy in -1..=1i64 {
+                if dx == 0 && dy == 0 {
+                    continue;
+                }
+
+                let x = at_x as i64 + dx;
+                let y = at_y as i64 + dy;
+                if x >= 0 && y >= 0 && (x as usize) < self.width && (y as usize) < self.height {
+                    result.push((x as usize, y as usize));
+                }
+            }
+        }
+
+        result
+    }
+
+    fn aggregate_result(&self) -> RevealResult {
+        let remaining_not_revealed = self
+            .cells
+            .iter()
+            .filter(|cell| matches!(cell, CellState::Hidden | CellState::Flagged))
+            .count();
+
+        if remaining_not_revealed == self.mine_count() {
+            RevealResult::Win
+        } else {
+            RevealResult::Continue
+        }
+    }
 
-        while to_reveal.len() > 0 {
-            let (x, y) = to_reveal.pop().unwrap();
+    fn record(&mut self, mv: crate::replay::Move, result: RevealResult) {
+        if let Some(log) = &mut self.log {
+            log.push((mv, result));
+        }
+    }
+
+    fn floodfill_reveal(&mut self, start: Coords) {
+        let mut to_reveal = vec![start];
 
+        while let Some((x, y)) = to_reveal.pop() {
             match self.cell_at((x, y)).unwrap() {
                 CellState::Hidden => {
                     let neighbor_mines = self.count_neighbor_mines((x, y));
-                    *self.cell_at_mut((x, y)).unwrap() = CellState::Revealed(neighbor_mines as u8);
+                    *self.cell_at_mut((x, y)).unwrap() = CellState::Revealed(neighbor_mines);
                     if neighbor_mines == 0 {
-                        to_reveal.push((x.saturating_add(1).min(self.width - 1), y));
-                        to_reveal.push((x.saturating_sub(1).min(self.width - 1), y));
-                        to_reveal.push((x, y.saturating_add(1).min(self.height - 1)));
-                        to_reveal.push((x, y.saturating_sub(1).min(self.height - 1)));
-
-                        to_reveal.push((
-                            x.saturating_add(1).min(self.width - 1),
-                            y.saturating_add(1).min(self.height - 1),
-                        ));
-                        to_reveal.push((
-                            x.saturating_sub(1).min(self.width - 1),
-                            y.saturating_add(1).min(self.height - 1),
-                        ));
-                        to_reveal.push((
-                            x.saturating_sub(1).min(self.width - 1),
-                            y.saturating_add(1).min(self.height - 1),
-                        ));
-                        to_reveal.push((
-                            x.saturating_sub(1).min(self.width - 1),
-                            y.saturating_sub(1).min(self.height - 1),
-                        ));
+                        to_reveal.extend(self.neighbors((x, y)));
                     }
                 }
                 CellState::Flagged | CellState::Revealed(..) => {}
@@ -196,16 +457,12 @@ impl Game {
         Ok(self.cells.get_mut(index).unwrap())
     }
 
-    fn count_neighbor_mines(&self, at: Coords) -> usize {
-        let (at_x, at_y) = at;
-        self.mines
-            .iter()
-            .filter(|(x, y)| x.abs_diff(at_x) <= 1 && y.abs_diff(at_y) <= 1)
-            .count()
+    fn count_neighbor_mines(&self, at: Coords) -> u8 {
+        self.neighbor_counts[self.index(at).unwrap()]
     }
 
     fn is_mine_at(&self, at: Coords) -> bool {
-        self.mines.contains(&at)
+        self.mine_grid[self.index(at).unwrap()]
     }
 
     fn index(&self, coords: Coords) -> Result<usize, InvalidCoords> {
@@ -221,7 +478,12 @@ impl Game {
     }
 }
 
-fn generate_random_mines(width: usize, height: usize, count: usize) -> Vec<Coords> {
+fn generate_random_mines(
+    width: usize,
+    height: usize,
+    count: usize,
+    excluded: &HashSet<Coords>,
+) -> Vec<Coords> {
     use rand::Rng;
 
     let mut rng = rand::thread_rng();
@@ -230,7 +492,7 @@ fn generate_random_mines(width: usize, height: usize, count: usize) -> Vec<Coord
 
     while generated.len() < count {
         let new_coords = (rng.gen_range(0..width), rng.gen_range(0..height));
-        if !generated.contains(&new_coords) {
+        if !excluded.contains(&new_coords) && !generated.contains(&new_coords) {
             generated.push(new_coords);
         }
     }
@@ -258,7 +520,8 @@ mod test {
         let game1 = Game::new(10, 15, 5).unwrap();
         assert_eq!(game1.field_width(), 10);
         assert_eq!(game1.field_height(), 15);
-        assert_eq!(game1.mine_count(), 5);
+        // Mines are placed lazily on the first reveal, so none exist yet.
+        assert_eq!(game1.mine_count(), 0);
 
         assert_all_fields(game1.field_width(), game1.field_height(), |coords| {
             game1.cell_at(coords).unwrap() == CellState::Hidden
@@ -311,6 +574,51 @@ mod test {
         );
     }
 
+    #[test]
+    fn first_click_is_always_safe() {
+        let mut game = Game::new(10, 10, 90).unwrap();
+        assert_eq!(game.mine_count(), 0);
+
+        let first_click = (5, 5);
+        let res = game.reveal(first_click).unwrap();
+        assert_ne!(res, RevealResult::GameOver);
+        assert_eq!(game.mine_count(), 90);
+
+        assert!(matches!(
+            game.cell_at(first_click).unwrap(),
+            CellState::Revealed(..)
+        ));
+        for (x, y) in [
+            (4, 4),
+            (5, 4),
+            (6, 4),
+            (4, 5),
+            (6, 5),
+            (4, 6),
+            (5, 6),
+            (6, 6),
+        ] {
+            assert!(matches!(
+                game.cell_at((x, y)).unwrap(),
+                CellState::Revealed(..)
+            ));
+        }
+    }
+
+    #[test]
+    fn first_click_rejects_too_small_a_field() {
+        let mut game = Game::new(2, 2, 4).unwrap();
+        let res = game.reveal((0, 0));
+        assert!(matches!(
+            res,
+            Err(RevealError::FieldTooSmall {
+                mines: 4,
+                available: 0,
+                ..
+            })
+        ));
+    }
+
     #[test]
     fn reveal_counting() {
         let mut game = Game::empty(3, 3);
@@ -403,4 +711,142 @@ mod test {
             ToggleFlagError::InvalidCoords(InvalidCoords { .. })
         ));
     }
+
+    #[test]
+    fn chord_reveals_satisfied_neighbors() {
+        let mut game = Game::empty(3, 3);
+        game.place_mine((2, 0)).unwrap();
+        game.place_mine((2, 1)).unwrap();
+        game.place_mine((1, 2)).unwrap();
+
+        game.reveal((0, 0)).unwrap();
+        assert_eq!(game.cell_at((1, 1)).unwrap(), CellState::Revealed(3));
+
+        assert_eq!(game.chord((1, 1)).unwrap(), RevealResult::Continue);
+        assert_eq!(game.cell_at((0, 2)).unwrap(), CellState::Hidden);
+
+        game.toggle_flag((2, 0)).unwrap();
+        game.toggle_flag((2, 1)).unwrap();
+        game.toggle_flag((1, 2)).unwrap();
+
+        let res = game.chord((1, 1)).unwrap();
+        assert_eq!(res, RevealResult::Win);
+        assert_eq!(game.cell_at((0, 2)).unwrap(), CellState::Revealed(1));
+        assert_eq!(game.cell_at((2, 2)).unwrap(), CellState::Revealed(2));
+    }
+
+    #[test]
+    fn chord_detonates_on_a_wrongly_flagged_neighbor() {
+        let mut game = Game::empty(3, 3);
+        game.place_mine((2, 0)).unwrap();
+        game.place_mine((2, 1)).unwrap();
+        game.place_mine((1, 2)).unwrap();
+
+        game.reveal((0, 0)).unwrap();
+
+        game.toggle_flag((2, 0)).unwrap();
+        game.toggle_flag((2, 1)).unwrap();
+        game.toggle_flag((2, 2)).unwrap();
+
+        let res = game.chord((1, 1)).unwrap();
+        assert_eq!(res, RevealResult::GameOver);
+    }
+
+    #[test]
+    fn chord_ignores_cells_that_are_not_satisfied_numbers() {
+        let mut game = Game::empty(5, 5);
+        assert_eq!(game.chord((2, 2)).unwrap(), RevealResult::Continue);
+
+        let res = game.chord((15, 15));
+        assert!(res.is_err());
+        assert!(matches!(res.unwrap_err(), InvalidCoords { .. }));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn save_and_load_round_trip() {
+        let mut game = Game::empty(5, 5);
+        game.place_mine((0, 0)).unwrap();
+        game.place_mine((4, 4)).unwrap();
+        game.toggle_flag((1, 1)).unwrap();
+        game.reveal((2, 2)).unwrap();
+
+        let mut buffer = Vec::new();
+        game.save_to_writer(&mut buffer).unwrap();
+
+        let loaded = Game::load_from_reader(buffer.as_slice()).unwrap();
+
+        assert_all_fields(game.field_width(), game.field_height(), |coords| {
+            loaded.cell_at(coords).unwrap() == game.cell_at(coords).unwrap()
+        });
+        assert_eq!(loaded.field_width(), game.field_width());
+        assert_eq!(loaded.field_height(), game.field_height());
+        assert_eq!(loaded.mine_count(), game.mine_count());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn save_and_load_round_trip_before_mines_are_placed() {
+        let game = Game::new(8, 8, 10).unwrap();
+
+        let mut buffer = Vec::new();
+        game.save_to_writer(&mut buffer).unwrap();
+
+        let mut loaded = Game::load_from_reader(buffer.as_slice()).unwrap();
+
+        assert_eq!(loaded.mine_count(), 0);
+        assert_ne!(loaded.reveal((3, 3)).unwrap(), RevealResult::GameOver);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn load_rejects_a_truncated_cell_grid() {
+        let mut game = Game::empty(5, 5);
+        game.place_mine((0, 0)).unwrap();
+        // Simulate a truncated/corrupted save file, the shape a partially
+        // written or edited-by-hand save would have.
+        game.cells.truncate(1);
+
+        let mut buffer = Vec::new();
+        game.save_to_writer(&mut buffer).unwrap();
+
+        let result = Game::load_from_reader(buffer.as_slice());
+        assert!(matches!(
+            result,
+            Err(LoadError::InvalidData(InvalidSaveData::CellCount { .. }))
+        ));
+    }
+
+    #[test]
+    fn neighbor_counts_match_a_brute_force_recount() {
+        let mut game = Game::empty(6, 6);
+        for mine in [(0, 0), (5, 5), (2, 3), (3, 3), (1, 5)] {
+            game.place_mine(mine).unwrap();
+        }
+
+        for y in 0..6 {
+            for x in 0..6 {
+                let brute_force = [(0, 0), (5, 5), (2, 3), (3, 3), (1, 5)]
+                    .into_iter()
+                    .filter(|&(mx, my): &(usize, usize)| {
+                        (mx, my) != (x, y) && mx.abs_diff(x) <= 1 && my.abs_diff(y) <= 1
+                    })
+                    .count() as u8;
+                assert_eq!(game.count_neighbor_mines((x, y)), brute_force);
+            }
+        }
+    }
+
+    #[test]
+    fn floodfill_reveals_the_entire_open_region_of_a_large_board() {
+        let mut game = Game::empty(40, 40);
+        game.place_mine((39, 39)).unwrap();
+
+        let res = game.reveal((0, 0)).unwrap();
+        assert_eq!(res, RevealResult::Win);
+
+        assert_all_fields(40, 40, |coords| {
+            coords == (39, 39) || matches!(game.cell_at(coords).unwrap(), CellState::Revealed(_))
+        });
+    }
 }
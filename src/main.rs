@@ -1,14 +1,29 @@
 mod core;
+mod replay;
 
 use core::{Game, RevealResult};
 use std::io::{self, Write};
 
 fn main() {
+    #[cfg(feature = "serde")]
+    {
+        let args: Vec<String> = std::env::args().collect();
+        if let [_, command, path] = args.as_slice() {
+            if command == "replay" {
+                replay_mode(path);
+                return;
+            }
+        }
+    }
+
     let game = init_game();
     game_loop(game);
 }
 
 fn game_loop(mut game: Game) {
+    #[cfg(feature = "serde")]
+    game.start_recording();
+
     loop {
         clear_screen();
         show_game(&game);
@@ -34,17 +49,137 @@ fn game_loop(mut game: Game) {
                     println!("{e}");
                 }
             },
+            Command::Chord(x, y) => match game.chord((x, y)) {
+                Ok(RevealResult::Continue) => {}
+                Ok(RevealResult::GameOver) => {
+                    println!("You lost :(");
+                    break;
+                }
+                Ok(RevealResult::Win) => {
+                    println!("Congratulations! You won!");
+                    break;
+                }
+                Err(e) => {
+                    println!("{e}");
+                }
+            },
+            #[cfg(feature = "serde")]
+            Command::Save(path) => match std::fs::File::create(&path) {
+                Ok(file) => match game.save_to_writer(file) {
+                    Ok(()) => println!("Saved to {path}"),
+                    Err(e) => println!("{e}"),
+                },
+                Err(e) => println!("Cannot create {path}: {e}"),
+            },
+            #[cfg(feature = "serde")]
+            Command::Load(path) => match std::fs::File::open(&path) {
+                Ok(file) => match Game::load_from_reader(file) {
+                    Ok(mut loaded) => {
+                        loaded.start_recording();
+                        game = loaded;
+                        println!("Loaded {path}");
+                    }
+                    Err(e) => println!("{e}"),
+                },
+                Err(e) => println!("Cannot open {path}: {e}"),
+            },
         }
     }
 
     clear_screen();
     show_game(&game);
+
+    #[cfg(feature = "serde")]
+    save_replay_log(&mut game);
+}
+
+#[cfg(feature = "serde")]
+fn save_replay_log(game: &mut Game) {
+    let Some((initial_cells, log)) = game.take_log() else {
+        return;
+    };
+    if log.is_empty() {
+        return;
+    }
+
+    let replay = replay::Replay::from_game_log(game, initial_cells, log);
+    match std::fs::File::create("last_game.replay") {
+        Ok(file) => match replay.save_to_writer(file) {
+            Ok(()) => println!("Replay saved to last_game.replay"),
+            Err(e) => println!("{e}"),
+        },
+        Err(e) => println!("Cannot create last_game.replay: {e}"),
+    }
+}
+
+#[cfg(feature = "serde")]
+fn replay_mode(path: &str) {
+    let file = match std::fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("Cannot open {path}: {e}");
+            return;
+        }
+    };
+
+    let mut replay = match replay::Replay::load_from_reader(file) {
+        Ok(replay) => replay,
+        Err(e) => {
+            eprintln!("{e}");
+            return;
+        }
+    };
+
+    let final_result = replay.moves().last().map(|(_, result)| result);
+
+    let mut board = replay.current();
+
+    loop {
+        clear_screen();
+        show_cells(replay.field_width(), replay.field_height(), &board);
+        if let Some(result) = &final_result {
+            println!("Game ended: {result:?}");
+        }
+        println!(
+            "\n\nMove {}/{} - Enter: step forward, b: step back, q: quit",
+            replay.current_index(),
+            replay.move_count()
+        );
+
+        let mut buffer = String::new();
+        io::stdin()
+            .read_line(&mut buffer)
+            .expect("Cannot read from stdin!");
+
+        match buffer.trim() {
+            "q" | "quit" => break,
+            "b" | "back" => {
+                if let Some(new_board) = replay.step_back() {
+                    board = new_board;
+                } else {
+                    println!("Already at the first move");
+                }
+            }
+            _ => {
+                if let Some(new_board) = replay.step_forward() {
+                    board = new_board;
+                } else {
+                    println!("Already at the last move");
+                }
+            }
+        }
+    }
 }
 
 #[derive(Debug)]
 enum Command {
     Reveal(usize, usize),
     ToggleFlag(usize, usize),
+    Chord(usize, usize),
+    #[cfg(feature = "serde")]
+    Save(String),
+    #[cfg(feature = "serde")]
+    Load(String),
 }
 
 fn read_command() -> Command {
@@ -57,55 +192,100 @@ fn read_command() -> Command {
 
         let parts: Vec<_> = buffer.split(' ').collect();
 
-        if parts.len() != 3 {
+        match parts[0].trim() {
+            "r" | "reveal" => match read_coords(&parts) {
+                Ok((x, y)) => return Command::Reveal(x, y),
+                Err(_) => continue,
+            },
+            "f" | "flag" => match read_coords(&parts) {
+                Ok((x, y)) => return Command::ToggleFlag(x, y),
+                Err(_) => continue,
+            },
+            "c" | "chord" => match read_coords(&parts) {
+                Ok((x, y)) => return Command::Chord(x, y),
+                Err(_) => continue,
+            },
+            #[cfg(feature = "serde")]
+            "save" => match read_path(&parts) {
+                Ok(path) => return Command::Save(path),
+                Err(_) => continue,
+            },
+            #[cfg(feature = "serde")]
+            "load" => match read_path(&parts) {
+                Ok(path) => return Command::Load(path),
+                Err(_) => continue,
+            },
+            other => {
+                println!("{} is not a command", other);
+            }
+        }
+    }
+}
+
+fn read_coords(parts: &[&str]) -> Result<(usize, usize), ()> {
+    match parts.len() {
+        3 => {
+            let x = parse_usize_arg(parts[1])?;
+            let y = parse_usize_arg(parts[2])?;
+            Ok((x, y))
+        }
+        2 => parse_letter_number_coords(parts[1].trim()),
+        _ => {
             println!("Not enough arguments!");
-            continue;
+            Err(())
         }
+    }
+}
 
-        match parts[0] {
-            "r" | "reveal" => {
-                let x = match parts[1].trim().parse::<usize>() {
-                    Ok(n) => n,
-                    Err(_) => {
-                        println!("`{}` is not a number", parts[1]);
-                        continue;
-                    }
-                };
+fn parse_usize_arg(s: &str) -> Result<usize, ()> {
+    match s.trim().parse() {
+        Ok(n) => Ok(n),
+        Err(_) => {
+            println!("`{}` is not a number", s);
+            Err(())
+        }
+    }
+}
 
-                let y = match parts[2].trim().parse::<usize>() {
-                    Ok(n) => n,
-                    Err(_) => {
-                        println!("`{}` is not a number", parts[2]);
-                        continue;
-                    }
-                };
+// Accepts the climinesweeper-style `a3` shorthand: a row letter followed by a column number.
+fn parse_letter_number_coords(token: &str) -> Result<(usize, usize), ()> {
+    let split_at = match token.find(|c: char| c.is_ascii_digit()) {
+        Some(i) if i > 0 => i,
+        _ => {
+            println!("`{token}` is not a valid coordinate, expected something like `a3`");
+            return Err(());
+        }
+    };
 
-                return Command::Reveal(x, y);
-            }
-            "f" | "flag" => {
-                let x = match parts[1].trim().parse::<usize>() {
-                    Ok(n) => n,
-                    Err(_) => {
-                        println!("`{}` is not a number", parts[1]);
-                        continue;
-                    }
-                };
+    let (letters, digits) = token.split_at(split_at);
 
-                let y = match parts[2].trim().parse::<usize>() {
-                    Ok(n) => n,
-                    Err(_) => {
-                        println!("`{}` is not a number", parts[2]);
-                        continue;
-                    }
-                };
+    let row = match row_label_to_index(letters) {
+        Some(row) => row,
+        None => {
+            println!("`{letters}` is not a valid row letter");
+            return Err(());
+        }
+    };
 
-                return Command::ToggleFlag(x, y);
-            }
-            _ => {
-                println!("{} is not a command", parts[0]);
-            }
+    let column = match digits.parse::<usize>() {
+        Ok(n) => n,
+        Err(_) => {
+            println!("`{digits}` is not a valid column number");
+            return Err(());
         }
+    };
+
+    Ok((column, row))
+}
+
+#[cfg(feature = "serde")]
+fn read_path(parts: &[&str]) -> Result<String, ()> {
+    if parts.len() != 2 {
+        println!("Not enough arguments!");
+        return Err(());
     }
+
+    Ok(parts[1].trim().to_owned())
 }
 
 fn show_prompt() {
@@ -149,21 +329,42 @@ fn read_usize_with_message(message: &str) -> usize {
 }
 
 fn show_game(game: &Game) {
+    render_board(game.field_width(), game.field_height(), |at| {
+        game.cell_at(at).unwrap()
+    });
+}
+
+#[cfg(feature = "serde")]
+fn show_cells(width: usize, height: usize, cells: &[core::CellState]) {
+    render_board(width, height, |(x, y)| cells[y * width + x]);
+}
+
+fn render_board(width: usize, height: usize, cell_at: impl Fn(core::Coords) -> core::CellState) {
     use core::CellState;
 
+    let label_width = row_label(height.saturating_sub(1)).len();
+
     let print_separator_line = || {
-        for _ in 0..game.field_width() {
+        print!("{:label_width$} ", "");
+        for _ in 0..width {
             print!("+---");
         }
         println!("+");
     };
 
+    print!("{:label_width$} ", "");
+    for x in 0..width {
+        print!(" {x:<3}");
+    }
+    println!();
+
     print_separator_line();
 
-    for y in 0..game.field_height() {
-        for x in 0..game.field_width() {
+    for y in 0..height {
+        print!("{:>label_width$} ", row_label(y));
+        for x in 0..width {
             print!("|");
-            match game.cell_at((x, y)).unwrap() {
+            match cell_at((x, y)) {
                 CellState::Hidden => print!("###"),
                 CellState::Flagged => print!("#!#"),
                 CellState::Revealed(0) => print!("   "),
@@ -174,3 +375,33 @@ fn show_game(game: &Game) {
         print_separator_line();
     }
 }
+
+// Spreadsheet-style row labels: a, b, ..., z, aa, ab, ...
+fn row_label(index: usize) -> String {
+    let mut index = index;
+    let mut label = Vec::new();
+
+    loop {
+        label.push((b'a' + (index % 26) as u8) as char);
+        if index < 26 {
+            break;
+        }
+        index = index / 26 - 1;
+    }
+
+    label.iter().rev().collect()
+}
+
+fn row_label_to_index(label: &str) -> Option<usize> {
+    if label.is_empty() || !label.chars().all(|c| c.is_ascii_alphabetic()) {
+        return None;
+    }
+
+    let mut index = 0usize;
+    for c in label.chars() {
+        let digit = (c.to_ascii_lowercase() as u8 - b'a') as usize;
+        index = index.checked_mul(26)?.checked_add(digit + 1)?;
+    }
+
+    Some(index - 1)
+}
@@ -0,0 +1,328 @@
+use crate::core::{CellState, Coords, Game, RevealResult};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Move {
+    Reveal(Coords),
+    ToggleFlag(Coords),
+    Chord(Coords),
+}
+
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+struct Seed {
+    width: usize,
+    height: usize,
+    mines: Vec<Coords>,
+    initial_cells: Vec<CellState>,
+}
+
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Replay {
+    seed: Seed,
+    moves: Vec<(Move, RevealResult)>,
+    cursor: usize,
+}
+
+#[cfg(feature = "serde")]
+#[derive(Debug, thiserror::Error)]
+#[error("Failed to save replay: {0}")]
+pub struct SaveError(#[from] ciborium::ser::Error<std::io::Error>);
+
+#[cfg(feature = "serde")]
+#[derive(Debug, thiserror::Error)]
+pub enum InvalidReplayData {
+    #[error("expected {expected} initial cells for a {width}x{height} field, got {actual}")]
+    InitialCellCount {
+        width: usize,
+        height: usize,
+        expected: usize,
+        actual: usize,
+    },
+    #[error("mine at {0:?} is out of bounds for a {1}x{2} field")]
+    MineOutOfBounds(Coords, usize, usize),
+    #[error("duplicate mine at {0:?}")]
+    DuplicateMine(Coords),
+    #[error("replay cursor {cursor} is out of range for {move_count} recorded moves")]
+    CursorOutOfRange { cursor: usize, move_count: usize },
+}
+
+#[cfg(feature = "serde")]
+#[derive(Debug, thiserror::Error)]
+pub enum LoadError {
+    #[error("Failed to load replay: {0}")]
+    Deserialize(#[from] ciborium::de::Error<std::io::Error>),
+    #[error("Failed to load replay: {0}")]
+    InvalidData(#[from] InvalidReplayData),
+}
+
+impl Replay {
+    // `initial_cells` is the board state when recording started: a log of moves
+    // alone can't reconstruct progress made before that (e.g. a mid-game save/load).
+    pub fn from_game_log(
+        game: &Game,
+        initial_cells: Vec<CellState>,
+        moves: Vec<(Move, RevealResult)>,
+    ) -> Self {
+        Self {
+            seed: Seed {
+                width: game.field_width(),
+                height: game.field_height(),
+                mines: game.mines().to_vec(),
+                initial_cells,
+            },
+            moves,
+            cursor: 0,
+        }
+    }
+
+    pub fn field_width(&self) -> usize {
+        self.seed.width
+    }
+
+    pub fn field_height(&self) -> usize {
+        self.seed.height
+    }
+
+    pub fn move_count(&self) -> usize {
+        self.moves.len()
+    }
+
+    pub fn current_index(&self) -> usize {
+        self.cursor
+    }
+
+    pub fn current(&self) -> Vec<CellState> {
+        self.board_at(self.cursor)
+    }
+
+    pub fn step_forward(&mut self) -> Option<Vec<CellState>> {
+        if self.cursor >= self.moves.len() {
+            return None;
+        }
+        self.cursor += 1;
+        Some(self.current())
+    }
+
+    pub fn step_back(&mut self) -> Option<Vec<CellState>> {
+        if self.cursor == 0 {
+            return None;
+        }
+        self.cursor -= 1;
+        Some(self.current())
+    }
+
+    pub fn moves(&self) -> impl Iterator<Item = (Move, RevealResult)> + '_ {
+        self.moves.iter().copied()
+    }
+
+    fn board_at(&self, move_count: usize) -> Vec<CellState> {
+        let mut game = Game::empty(self.seed.width, self.seed.height);
+        for &mine in &self.seed.mines {
+            game.place_mine(mine).unwrap();
+        }
+        game.restore_cells(self.seed.initial_cells.clone());
+
+        for &(mv, _) in self.moves.iter().take(move_count) {
+            apply_move(&mut game, mv);
+        }
+
+        (0..self.seed.height)
+            .flat_map(|y| (0..self.seed.width).map(move |x| (x, y)))
+            .map(|at| game.cell_at(at).unwrap())
+            .collect()
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn save_to_writer(&self, writer: impl std::io::Write) -> Result<(), SaveError> {
+        ciborium::ser::into_writer(self, writer)?;
+        Ok(())
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn load_from_reader(reader: impl std::io::Read) -> Result<Self, LoadError> {
+        let replay: Self = ciborium::de::from_reader(reader)?;
+        replay.validate()?;
+        Ok(replay)
+    }
+
+    #[cfg(feature = "serde")]
+    fn validate(&self) -> Result<(), InvalidReplayData> {
+        let expected = self.seed.width * self.seed.height;
+        if self.seed.initial_cells.len() != expected {
+            return Err(InvalidReplayData::InitialCellCount {
+                width: self.seed.width,
+                height: self.seed.height,
+                expected,
+                actual: self.seed.initial_cells.len(),
+            });
+        }
+
+        let mut seen = std::collections::HashSet::new();
+        for &mine in &self.seed.mines {
+            if mine.0 >= self.seed.width || mine.1 >= self.seed.height {
+                return Err(InvalidReplayData::MineOutOfBounds(
+                    mine,
+                    self.seed.width,
+                    self.seed.height,
+                ));
+            }
+            if !seen.insert(mine) {
+                return Err(InvalidReplayData::DuplicateMine(mine));
+            }
+        }
+
+        if self.cursor > self.moves.len() {
+            return Err(InvalidReplayData::CursorOutOfRange {
+                cursor: self.cursor,
+                move_count: self.moves.len(),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+fn apply_move(game: &mut Game, mv: Move) {
+    match mv {
+        Move::Reveal(at) => {
+            let _ = game.reveal(at);
+        }
+        Move::ToggleFlag(at) => {
+            let _ = game.toggle_flag(at);
+        }
+        Move::Chord(at) => {
+            let _ = game.chord(at);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn step_forward_and_back_reconstruct_intermediate_boards() {
+        let mut game = Game::empty(3, 3);
+        game.place_mine((2, 0)).unwrap();
+        game.place_mine((2, 1)).unwrap();
+        game.place_mine((1, 2)).unwrap();
+        game.start_recording();
+
+        game.reveal((0, 0)).unwrap();
+        game.toggle_flag((2, 0)).unwrap();
+        let final_result = game.reveal((0, 2)).unwrap();
+
+        let (initial_cells, log) = game.take_log().unwrap();
+        assert_eq!(log.len(), 3);
+
+        let mut replay = Replay::from_game_log(&game, initial_cells, log);
+        assert_eq!(replay.move_count(), 3);
+        assert_eq!(replay.current(), vec![CellState::Hidden; 9]);
+
+        let after_first = replay.step_forward().unwrap();
+        assert_eq!(after_first[0], CellState::Revealed(0));
+
+        replay.step_forward().unwrap();
+        let after_third = replay.step_forward().unwrap();
+        assert!(replay.step_forward().is_none());
+
+        let mut final_game = Game::empty(3, 3);
+        final_game.place_mine((2, 0)).unwrap();
+        final_game.place_mine((2, 1)).unwrap();
+        final_game.place_mine((1, 2)).unwrap();
+        final_game.reveal((0, 0)).unwrap();
+        final_game.toggle_flag((2, 0)).unwrap();
+        final_game.reveal((0, 2)).unwrap();
+        for y in 0..3 {
+            for x in 0..3 {
+                assert_eq!(
+                    after_third[y * 3 + x],
+                    final_game.cell_at((x, y)).unwrap()
+                );
+            }
+        }
+
+        let _ = replay.step_back();
+        let back_to_first = replay.step_back().unwrap();
+        assert_eq!(back_to_first, after_first);
+
+        assert_eq!(final_result, RevealResult::Continue);
+        assert_eq!(
+            replay.moves().map(|(_, result)| result).collect::<Vec<_>>(),
+            vec![
+                RevealResult::Continue,
+                RevealResult::Continue,
+                RevealResult::Continue
+            ]
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn replay_survives_a_save_and_load_mid_recording() {
+        let mut game = Game::empty(3, 3);
+        game.place_mine((2, 0)).unwrap();
+        game.place_mine((2, 1)).unwrap();
+        game.place_mine((1, 2)).unwrap();
+        game.start_recording();
+        game.reveal((0, 0)).unwrap();
+
+        let mut buffer = Vec::new();
+        game.save_to_writer(&mut buffer).unwrap();
+
+        // Mimic the `load` command: the freshly-deserialized game has no log of
+        // its own, so recording has to be re-armed on top of the loaded state.
+        let mut game = Game::load_from_reader(buffer.as_slice()).unwrap();
+        game.start_recording();
+
+        let loaded_cells: Vec<_> = (0..3)
+            .flat_map(|y| (0..3).map(move |x| (x, y)))
+            .map(|at| game.cell_at(at).unwrap())
+            .collect();
+
+        game.toggle_flag((2, 0)).unwrap();
+
+        let (initial_cells, log) = game.take_log().unwrap();
+        assert_eq!(log.len(), 1);
+
+        let mut replay = Replay::from_game_log(&game, initial_cells, log);
+
+        // The first reveal happened before the save, so it must already be
+        // present at cursor 0 instead of replay starting from a blank board.
+        let before_reload = replay.current();
+        assert_eq!(before_reload, loaded_cells);
+        assert_eq!(before_reload[0], CellState::Revealed(0));
+
+        let after_flag = replay.step_forward().unwrap();
+        assert_eq!(after_flag[2], CellState::Flagged);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn load_rejects_a_truncated_initial_cell_grid() {
+        let mut game = Game::empty(3, 3);
+        game.place_mine((2, 0)).unwrap();
+        game.start_recording();
+        game.reveal((0, 0)).unwrap();
+        let (initial_cells, log) = game.take_log().unwrap();
+
+        let mut replay = Replay::from_game_log(&game, initial_cells, log);
+        // Simulate a truncated/corrupted save file, the shape a partially
+        // written or edited-by-hand save would have.
+        replay.seed.initial_cells.truncate(1);
+
+        let mut buffer = Vec::new();
+        replay.save_to_writer(&mut buffer).unwrap();
+
+        let result = Replay::load_from_reader(buffer.as_slice());
+        assert!(matches!(
+            result,
+            Err(LoadError::InvalidData(
+                InvalidReplayData::InitialCellCount { .. }
+            ))
+        ));
+    }
+}